@@ -1,12 +1,75 @@
 use clap::{Parser, Subcommand};
 use colored::*;
-use std::io::{self, BufRead};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 
+/// Commands a client can send to the server, one per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ClientCommand {
+    /// Plain chat text to broadcast.
+    Msg(String),
+    /// Ask the server for the current list of connected usernames.
+    ReqClients,
+    /// Rename the connection's username.
+    Nick(String),
+    /// Close the connection.
+    Quit,
+}
+
+impl ClientCommand {
+    /// Parses a single line of the wire protocol. Lines that don't match a
+    /// known verb fall back to plain chat text, a permissive default that
+    /// avoids rejecting input over recognizing an unfamiliar command.
+    fn parse(line: &str) -> Self {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(rest) = line.strip_prefix("MSG ") {
+            ClientCommand::Msg(rest.to_string())
+        } else if line == "REQ CLIENTS" {
+            ClientCommand::ReqClients
+        } else if let Some(rest) = line.strip_prefix("NICK ") {
+            ClientCommand::Nick(rest.trim().to_string())
+        } else if line == "QUIT" {
+            ClientCommand::Quit
+        } else {
+            ClientCommand::Msg(line.to_string())
+        }
+    }
+
+    /// Serializes this command back into a wire protocol line (no trailing
+    /// newline).
+    fn to_wire(&self) -> String {
+        match self {
+            ClientCommand::Msg(text) => format!("MSG {}", text),
+            ClientCommand::ReqClients => "REQ CLIENTS".to_string(),
+            ClientCommand::Nick(name) => format!("NICK {}", name),
+            ClientCommand::Quit => "QUIT".to_string(),
+        }
+    }
+
+    /// Maps a line of client stdin onto a command: slash-syntax becomes the
+    /// matching verb, everything else is chat text.
+    fn from_stdin(input: &str) -> Self {
+        if let Some(name) = input.strip_prefix("/nick ") {
+            ClientCommand::Nick(name.trim().to_string())
+        } else if input == "/who" {
+            ClientCommand::ReqClients
+        } else if input == "/quit" {
+            ClientCommand::Quit
+        } else {
+            ClientCommand::Msg(input.to_string())
+        }
+    }
+}
+
 #[derive(Parser)]
 #[command(name = "chat-app")]
 #[command(about = "Chat application with client and server")]
@@ -15,11 +78,44 @@ struct Cli {
     command: Commands,
 }
 
+/// Which line protocol a server speaks to its connections.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Protocol {
+    /// The bundled client's `MSG`/`REQ CLIENTS`/`NICK`/`QUIT` protocol.
+    Native,
+    /// Enough of the IRC line protocol for real IRC clients to connect.
+    Irc,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Server {
         #[arg(short, long, default_value = "8080")]
         port: u16,
+        /// strftime-style format applied to the timestamp prefixed onto
+        /// every broadcast message and system notice in the native
+        /// protocol, and stored with each history entry. IRC framing
+        /// carries no timestamp.
+        #[arg(long, default_value = "%H:%M:%S")]
+        timestamp_format: String,
+        /// Line protocol to speak to connections. `irc` lets standard IRC
+        /// clients (irssi, HexChat, ...) join instead of the bundled client.
+        /// The server is still a single shared room: PRIVMSG is relayed to
+        /// every connection regardless of the channel named in JOIN or
+        /// PRIVMSG, only framed with whatever channel the sender used.
+        #[arg(long, value_enum, default_value = "native")]
+        protocol: Protocol,
+        /// Append every broadcast chat message as a JSON line to this file,
+        /// and replay the tail of it to newly connecting clients.
+        #[arg(long)]
+        history: Option<PathBuf>,
+        /// How many lines of history to replay to a newly connecting client.
+        #[arg(long, default_value_t = 20)]
+        history_replay: usize,
+        /// Serve Prometheus text-format metrics on this port, separate
+        /// from the chat socket. Disabled unless set.
+        #[arg(long)]
+        metrics_port: Option<u16>,
     },
     Client {
         #[arg(short, long)]
@@ -34,8 +130,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Server { port } => {
-            run_server(port).await?;
+        Commands::Server { port, timestamp_format, protocol, history, history_replay, metrics_port } => {
+            run_server(port, timestamp_format, protocol, history, history_replay, metrics_port).await?;
         }
         Commands::Client { address, username } => {
             run_client(address, username).await?;
@@ -45,63 +141,676 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-async fn run_server(port: u16) -> Result<(), Box<dyn std::error::Error>> {
+/// IRC channel used for chat that doesn't carry a real target: the native
+/// protocol has no channel concept, and history predates channel tracking.
+const DEFAULT_IRC_CHANNEL: &str = "#general";
+
+/// Events relayed to clients over the broadcast channel. Each carries the
+/// timestamp formatted when the event was sent, so every client displays
+/// the same time regardless of its own clock.
+#[derive(Debug, Clone)]
+enum ServerEvent {
+    /// Chat text from `from`; not echoed back to its own sender. `channel`
+    /// is the IRC target it was sent to (`#general` for native-protocol
+    /// traffic, which has no channel concept); the server is a single
+    /// shared room regardless, so this only affects how IRC clients frame
+    /// the message, not who receives it.
+    Chat { ts: String, from: String, text: String, channel: String },
+    /// A system notice shown to every connected client.
+    System { ts: String, text: String },
+}
+
+impl ServerEvent {
+    /// Builds a `System` event, stamping it with the current time in `fmt`.
+    fn system(fmt: &str, text: String) -> Self {
+        ServerEvent::System { ts: Self::stamp(fmt), text }
+    }
+
+    fn stamp(fmt: &str) -> String {
+        chrono::Local::now().format(fmt).to_string()
+    }
+
+    /// Renders this event for an IRC client: `PRIVMSG`/`NOTICE` framing,
+    /// addressed to the channel the message was actually sent to. The
+    /// server itself is still a single shared room — every connection
+    /// receives every message regardless of channel — so this only
+    /// controls which window the message lands in on the client side.
+    fn to_irc(&self, recipient: &str) -> String {
+        match self {
+            ServerEvent::Chat { from, text, channel, .. } => {
+                format!(":{0}!{0}@labrustchat PRIVMSG {1} :{2}", from, channel, text)
+            }
+            ServerEvent::System { text, .. } => {
+                format!(":labrustchat NOTICE {} :{}", recipient, text)
+            }
+        }
+    }
+
+    fn to_wire(&self) -> String {
+        match self {
+            ServerEvent::Chat { ts, from, text, .. } => format!("[{}] {}: {}", ts, from, text),
+            ServerEvent::System { ts, text } => format!("[{}] * {}", ts, text),
+        }
+    }
+}
+
+/// A chat message as persisted to the history file, one JSON object per
+/// line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    ts: String,
+    user: String,
+    body: String,
+}
+
+impl ChatMessage {
+    /// Turns a persisted message back into a `Chat` event so replay can
+    /// reuse the same wire framing as live traffic.
+    fn as_event(&self) -> ServerEvent {
+        ServerEvent::Chat {
+            ts: self.ts.clone(),
+            from: self.user.clone(),
+            text: self.body.clone(),
+            channel: DEFAULT_IRC_CHANNEL.to_string(),
+        }
+    }
+}
+
+/// Appends broadcast chat messages to a JSON-lines file and replays its
+/// tail to newly connecting clients.
+#[derive(Clone)]
+struct HistoryStore {
+    path: Arc<PathBuf>,
+    replay_count: usize,
+}
+
+impl HistoryStore {
+    fn new(path: PathBuf, replay_count: usize) -> Self {
+        HistoryStore { path: Arc::new(path), replay_count }
+    }
+
+    async fn append(&self, message: &ChatMessage) {
+        let path = self.path.as_ref().clone();
+        let Ok(line) = serde_json::to_string(message) else { return };
+        let _ = tokio::task::spawn_blocking(move || -> io::Result<()> {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+            writeln!(file, "{}", line)
+        })
+        .await;
+    }
+
+    /// Replays up to `replay_count` most recent messages, oldest first.
+    async fn tail(&self) -> Vec<ChatMessage> {
+        let path = self.path.as_ref().clone();
+        let n = self.replay_count;
+        tokio::task::spawn_blocking(move || read_tail_messages(&path, n))
+            .await
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the last `n` JSON-line messages from `path` without loading the
+/// whole file, seeking backward in fixed-size chunks until enough
+/// newlines have been seen or the start of the file is reached.
+fn read_tail_messages(path: &Path, n: usize) -> Vec<ChatMessage> {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return Vec::new();
+    };
+    let Ok(mut pos) = file.seek(SeekFrom::End(0)) else {
+        return Vec::new();
+    };
+
+    const CHUNK: u64 = 8192;
+    let mut newline_count = 0usize;
+    let mut buf = Vec::new();
+
+    while pos > 0 && newline_count <= n {
+        let read_size = CHUNK.min(pos);
+        pos -= read_size;
+        if file.seek(SeekFrom::Start(pos)).is_err() {
+            break;
+        }
+        let mut chunk = vec![0u8; read_size as usize];
+        if file.read_exact(&mut chunk).is_err() {
+            break;
+        }
+        newline_count += chunk.iter().filter(|&&b| b == b'\n').count();
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    let lines: Vec<&str> = text.lines().filter(|l| !l.is_empty()).collect();
+    let skip = lines.len().saturating_sub(n);
+    lines[skip..].iter().filter_map(|l| serde_json::from_str(l).ok()).collect()
+}
+
+/// A single connected user, keyed by an incrementing id so renames don't
+/// disturb the registry's identity of the connection.
+#[derive(Debug, Clone)]
+struct ChatUser {
+    id: u64,
+    username: String,
+}
+
+struct ChatServerState {
+    users: BTreeMap<u64, ChatUser>,
+    next_id: u64,
+}
+
+/// Shared registry of connected users. Cheap to clone; every clone sees the
+/// same underlying map.
+#[derive(Clone)]
+struct ChatServer {
+    state: Arc<Mutex<ChatServerState>>,
+}
+
+impl ChatServer {
+    fn new() -> Self {
+        ChatServer {
+            state: Arc::new(Mutex::new(ChatServerState {
+                users: BTreeMap::new(),
+                next_id: 1,
+            })),
+        }
+    }
+
+    /// Registers a new connection under `username`, assigning it the next
+    /// user id. Rejects the registration if the name is already in use.
+    fn register(&self, username: &str) -> Result<u64, String> {
+        let mut state = self.state.lock().unwrap();
+        if username.trim().is_empty() {
+            return Err("username must not be empty".to_string());
+        }
+        if state.users.values().any(|u| u.username == username) {
+            return Err(format!("username '{}' is already taken", username));
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.users.insert(id, ChatUser { id, username: username.to_string() });
+        Ok(id)
+    }
+
+    /// Renames the user with `id`. Rejects the rename if another connection
+    /// already holds `new_username`.
+    fn rename(&self, id: u64, new_username: &str) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if new_username.trim().is_empty() {
+            return Err("username must not be empty".to_string());
+        }
+        if state.users.values().any(|u| u.id != id && u.username == new_username) {
+            return Err(format!("username '{}' is already taken", new_username));
+        }
+        if let Some(user) = state.users.get_mut(&id) {
+            user.username = new_username.to_string();
+        }
+        Ok(())
+    }
+
+    /// Removes `id` from the registry, returning its username at the time
+    /// of removal if it was still present.
+    fn deregister(&self, id: u64) -> Option<String> {
+        self.state.lock().unwrap().users.remove(&id).map(|u| u.username)
+    }
+
+    fn usernames(&self) -> Vec<String> {
+        self.state.lock().unwrap().users.values().map(|u| u.username.clone()).collect()
+    }
+
+    fn user_count(&self) -> usize {
+        self.state.lock().unwrap().users.len()
+    }
+}
+
+/// Deregisters and announces the departure of a connection no matter which
+/// path the connection's task exits through (clean `QUIT`, EOF, or error).
+/// `mark_broken` distinguishes an uncleanly severed connection from an
+/// explicit `QUIT` in the departure notice.
+struct UserGuard {
+    server: ChatServer,
+    tx: broadcast::Sender<ServerEvent>,
+    timestamp_format: Arc<str>,
+    id: u64,
+    broken: Cell<bool>,
+}
+
+impl UserGuard {
+    fn mark_broken(&self) {
+        self.broken.set(true);
+    }
+}
+
+impl Drop for UserGuard {
+    fn drop(&mut self) {
+        if let Some(username) = self.server.deregister(self.id) {
+            let suffix = if self.broken.get() { " (broken pipe)" } else { "" };
+            let event = ServerEvent::system(&self.timestamp_format, format!("{} left the chat{}", username, suffix));
+            let _ = self.tx.send(event);
+        }
+    }
+}
+
+/// Increments the live-connections gauge on creation and decrements it on
+/// drop, so every exit path out of a connection's task (including an
+/// early return before registration succeeds) is accounted for.
+struct ConnectionGuard(Option<Metrics>);
+
+impl ConnectionGuard {
+    fn new(metrics: Option<Metrics>) -> Self {
+        if let Some(metrics) = &metrics {
+            metrics.connections.inc();
+        }
+        ConnectionGuard(metrics)
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.0 {
+            metrics.connections.dec();
+        }
+    }
+}
+
+/// Stamps, persists (if history is enabled) and broadcasts a chat message.
+/// `channel` is the IRC target the message was framed for (pass
+/// `DEFAULT_IRC_CHANNEL` for native-protocol senders, which have no
+/// channel concept); it only affects IRC framing, not delivery, since the
+/// server remains a single shared room.
+async fn broadcast_chat(
+    tx: &broadcast::Sender<ServerEvent>,
+    history: &Option<HistoryStore>,
+    metrics: &Option<Metrics>,
+    timestamp_format: &str,
+    from: String,
+    text: String,
+    channel: String,
+) {
+    let ts = ServerEvent::stamp(timestamp_format);
+    if let Some(history) = history {
+        history.append(&ChatMessage { ts: ts.clone(), user: from.clone(), body: text.clone() }).await;
+    }
+    if let Some(metrics) = metrics {
+        metrics.messages_total.inc();
+    }
+    let _ = tx.send(ServerEvent::Chat { ts, from, text, channel });
+}
+
+/// Prometheus counters/gauges for the chat server, served as plain text
+/// over a separate port so scrapers never touch the chat socket.
+#[derive(Clone)]
+struct Metrics {
+    registry: prometheus::Registry,
+    connections: prometheus::IntGauge,
+    messages_total: prometheus::IntCounter,
+    bytes_relayed_total: prometheus::IntCounter,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = prometheus::Registry::new();
+        let connections = prometheus::IntGauge::new("chat_connections", "Current live connections")?;
+        let messages_total =
+            prometheus::IntCounter::new("chat_messages_total", "Total chat messages broadcast")?;
+        let bytes_relayed_total =
+            prometheus::IntCounter::new("chat_bytes_relayed_total", "Total bytes relayed to clients")?;
+        registry.register(Box::new(connections.clone()))?;
+        registry.register(Box::new(messages_total.clone()))?;
+        registry.register(Box::new(bytes_relayed_total.clone()))?;
+        Ok(Metrics { registry, connections, messages_total, bytes_relayed_total })
+    }
+}
+
+/// Serves `GET /metrics` (and anything else, for simplicity) as the
+/// Prometheus text exposition format on `port`.
+async fn serve_metrics(port: u16, metrics: Metrics) {
+    let listener = match TcpListener::bind(format!("0.0.0.0:{}", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("{}", format!("Failed to bind metrics port {}: {}", port, e).red());
+            return;
+        }
+    };
+    println!("{}", format!("Metrics listening on port {}", port).green());
+
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+
+            let mut body = String::new();
+            if prometheus::TextEncoder::new()
+                .encode_utf8(&metrics.registry.gather(), &mut body)
+                .is_err()
+            {
+                return;
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Bundles the state every per-connection task needs a clone of, so accepting
+/// a connection and spawning its handler doesn't require a growing list of
+/// positional arguments.
+#[derive(Clone)]
+struct ConnContext {
+    tx: broadcast::Sender<ServerEvent>,
+    chat_server: ChatServer,
+    timestamp_format: Arc<str>,
+    history: Option<HistoryStore>,
+    metrics: Option<Metrics>,
+}
+
+async fn run_server(
+    port: u16,
+    timestamp_format: String,
+    protocol: Protocol,
+    history: Option<PathBuf>,
+    history_replay: usize,
+    metrics_port: Option<u16>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-    println!("{}", format!("Server listening on port {}", port).green());
+    println!(
+        "{}",
+        format!("Server listening on port {} ({:?} protocol)", port, protocol).green()
+    );
+
+    let (tx, _rx) = broadcast::channel::<ServerEvent>(100);
+    let chat_server = ChatServer::new();
+    let timestamp_format: Arc<str> = Arc::from(timestamp_format.as_str());
+    let history = history.map(|path| HistoryStore::new(path, history_replay));
+
+    let metrics = match metrics_port {
+        Some(port) => match Metrics::new() {
+            Ok(metrics) => {
+                tokio::spawn(serve_metrics(port, metrics.clone()));
+                Some(metrics)
+            }
+            Err(e) => {
+                eprintln!("{}", format!("Failed to initialize metrics: {}", e).red());
+                None
+            }
+        },
+        None => None,
+    };
 
-    let (tx, _rx) = broadcast::channel(100);
+    let ctx = ConnContext { tx, chat_server, timestamp_format, history, metrics };
 
     loop {
-        let (mut socket, addr) = listener.accept().await?;
+        let (socket, addr) = listener.accept().await?;
         println!("{}", format!("New connection from {}", addr).cyan());
 
-        let tx = tx.clone();
-        let mut rx = tx.subscribe();
+        let ctx = ctx.clone();
+        let rx = ctx.tx.subscribe();
 
-        tokio::spawn(async move {
-            let (reader, mut writer) = socket.split();
-            let mut reader = BufReader::new(reader);
-            let mut line = String::new();
+        match protocol {
+            Protocol::Native => {
+                tokio::spawn(handle_native_connection(socket, addr, ctx, rx));
+            }
+            Protocol::Irc => {
+                tokio::spawn(handle_irc_connection(socket, addr, ctx, rx));
+            }
+        }
+    }
+}
 
-            reader.read_line(&mut line).await.unwrap();
-            let username = line.trim().to_string();
-            line.clear();
+async fn handle_native_connection(
+    mut socket: TcpStream,
+    addr: std::net::SocketAddr,
+    ctx: ConnContext,
+    mut rx: broadcast::Receiver<ServerEvent>,
+) {
+    let ConnContext { tx, chat_server, timestamp_format, history, metrics } = ctx;
+    let _connection_guard = ConnectionGuard::new(metrics.clone());
 
-            println!("{}", format!("User '{}' connected from {}", username, addr).green());
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    reader.read_line(&mut line).await.unwrap();
+    let mut username = line.trim().to_string();
+    line.clear();
+
+    let id = match chat_server.register(&username) {
+        Ok(id) => id,
+        Err(e) => {
+            let _ = writer.write_all(format!("ERROR: {}\n", e).as_bytes()).await;
+            return;
+        }
+    };
+    let guard = UserGuard {
+        server: chat_server.clone(),
+        tx: tx.clone(),
+        timestamp_format: timestamp_format.clone(),
+        id,
+        broken: Cell::new(false),
+    };
+
+    println!("{}", format!("User '{}' connected from {}", username, addr).green());
+    let _ = tx.send(ServerEvent::system(&timestamp_format, format!("{} joined the chat", username)));
+
+    let welcome = format!("Welcome to the chat, {}!\n", username);
+    if writer.write_all(welcome.as_bytes()).await.is_err() {
+        guard.mark_broken();
+        return;
+    }
 
-            let welcome = format!("Welcome to the chat, {}!\n", username);
-            if writer.write_all(welcome.as_bytes()).await.is_err() {
+    if let Some(history) = &history {
+        for message in history.tail().await {
+            let line = format!("{}\n", message.as_event().to_wire());
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                guard.mark_broken();
                 return;
             }
+        }
+    }
 
-            loop {
-                tokio::select! {
-                    result = reader.read_line(&mut line) => {
-                        if result.unwrap_or(0) == 0 {
-                            break;
-                        }
+    loop {
+        tokio::select! {
+            result = reader.read_line(&mut line) => {
+                if result.unwrap_or(0) == 0 {
+                    guard.mark_broken();
+                    break;
+                }
 
-                        let message = line.trim();
-                        if !message.is_empty() {
-                            let formatted_message = format!("{}: {}\n", username, message);
-                            let _ = tx.send((formatted_message.clone(), username.clone()));
+                match ClientCommand::parse(&line) {
+                    ClientCommand::Msg(text) => {
+                        if !text.is_empty() {
+                            broadcast_chat(&tx, &history, &metrics, &timestamp_format, username.clone(), text, DEFAULT_IRC_CHANNEL.to_string()).await;
+                        }
+                    }
+                    ClientCommand::ReqClients => {
+                        let names = chat_server.usernames();
+                        let roster_line = format!("CLIENTS ({}): {}\n", chat_server.user_count(), names.join(", "));
+                        if writer.write_all(roster_line.as_bytes()).await.is_err() {
+                            guard.mark_broken();
+                            break;
                         }
-                        line.clear();
                     }
-                    result = rx.recv() => {
-                        let (msg, sender) = result.unwrap();
-                        if sender != username {
-                            if writer.write_all(msg.as_bytes()).await.is_err() {
-                                break;
+                    ClientCommand::Nick(new_name) => {
+                        match chat_server.rename(id, &new_name) {
+                            Ok(()) => {
+                                let notice = ServerEvent::system(&timestamp_format, format!("{} is now known as {}", username, new_name));
+                                let _ = tx.send(notice);
+                                username = new_name;
+                            }
+                            Err(e) => {
+                                let _ = writer.write_all(format!("ERROR: {}\n", e).as_bytes()).await;
                             }
                         }
                     }
+                    ClientCommand::Quit => {
+                        break;
+                    }
+                }
+                line.clear();
+            }
+            result = rx.recv() => {
+                let event = result.unwrap();
+                let skip = matches!(&event, ServerEvent::Chat { from, .. } if *from == username);
+                if !skip {
+                    let line = format!("{}\n", event.to_wire());
+                    if writer.write_all(line.as_bytes()).await.is_err() {
+                        guard.mark_broken();
+                        break;
+                    }
+                    if let Some(metrics) = &metrics {
+                        metrics.bytes_relayed_total.inc_by(line.len() as u64);
+                    }
+                }
+            }
+        }
+    }
+
+    println!("{}", format!("User '{}' disconnected", username).yellow());
+}
+
+/// Minimal IRC registration/relay handling so a real IRC client (irssi,
+/// HexChat, ...) can connect. Every joined channel maps onto the single
+/// room the native protocol already broadcasts to, so `PRIVMSG` simply
+/// mirrors `MSG` with IRC framing rather than isolating traffic per
+/// channel.
+async fn handle_irc_connection(
+    mut socket: TcpStream,
+    addr: std::net::SocketAddr,
+    ctx: ConnContext,
+    mut rx: broadcast::Receiver<ServerEvent>,
+) {
+    let ConnContext { tx, chat_server, timestamp_format, history, metrics } = ctx;
+    let _connection_guard = ConnectionGuard::new(metrics.clone());
+
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    let mut nick: Option<String> = None;
+    let mut id: Option<u64> = None;
+    let mut guard: Option<UserGuard> = None;
+
+    while id.is_none() {
+        line.clear();
+        if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+
+        if let Some(requested) = trimmed.strip_prefix("NICK ") {
+            let requested = requested.trim().to_string();
+            match chat_server.register(&requested) {
+                Ok(new_id) => {
+                    nick = Some(requested.clone());
+                    id = Some(new_id);
+                    guard = Some(UserGuard {
+                        server: chat_server.clone(),
+                        tx: tx.clone(),
+                        timestamp_format: timestamp_format.clone(),
+                        id: new_id,
+                        broken: Cell::new(false),
+                    });
+                    let welcome = format!(
+                        ":labrustchat 001 {0} :Welcome to the chat, {0}\r\n:labrustchat NOTICE {0} :Connected to labRustChat\r\n",
+                        requested
+                    );
+                    if writer.write_all(welcome.as_bytes()).await.is_err() {
+                        if let Some(guard) = &guard {
+                            guard.mark_broken();
+                        }
+                        return;
+                    }
+                    let _ = tx.send(ServerEvent::system(&timestamp_format, format!("{} joined the chat", requested)));
+                }
+                Err(e) => {
+                    let reply = format!(":labrustchat 433 * {} :{}\r\n", requested, e);
+                    if writer.write_all(reply.as_bytes()).await.is_err() {
+                        return;
+                    }
                 }
             }
+        }
+        // USER and other pre-registration commands are accepted and ignored;
+        // only NICK is needed to assign the registry identity.
+    }
 
-            println!("{}", format!("User '{}' disconnected", username).yellow());
-        });
+    let username = nick.unwrap();
+    let guard = guard.unwrap();
+    println!("{}", format!("IRC user '{}' connected from {}", username, addr).green());
+
+    if let Some(history) = &history {
+        for message in history.tail().await {
+            let line = format!("{}\r\n", message.as_event().to_irc(&username));
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                guard.mark_broken();
+                return;
+            }
+        }
+    }
+
+    loop {
+        tokio::select! {
+            result = reader.read_line(&mut line) => {
+                if result.unwrap_or(0) == 0 {
+                    guard.mark_broken();
+                    break;
+                }
+                let trimmed = line.trim_end_matches(['\r', '\n']).to_string();
+
+                if let Some(token) = trimmed.strip_prefix("PING ") {
+                    if writer.write_all(format!("PONG {}\r\n", token).as_bytes()).await.is_err() {
+                        guard.mark_broken();
+                        break;
+                    }
+                } else if let Some(rest) = trimmed.strip_prefix("PRIVMSG ") {
+                    if let Some((target, text)) = rest.split_once(" :") {
+                        if !text.is_empty() {
+                            broadcast_chat(&tx, &history, &metrics, &timestamp_format, username.clone(), text.to_string(), target.to_string()).await;
+                        }
+                    }
+                } else if let Some(channels) = trimmed.strip_prefix("JOIN ") {
+                    // Single shared room: acknowledge without tracking per-channel membership.
+                    let channels = channels.split_whitespace().next().unwrap_or("");
+                    let mut ack = String::new();
+                    for channel in channels.split(',').filter(|c| !c.is_empty()) {
+                        ack.push_str(&format!(":{0}!{0}@labrustchat JOIN {1}\r\n", username, channel));
+                    }
+                    if writer.write_all(ack.as_bytes()).await.is_err() {
+                        guard.mark_broken();
+                        break;
+                    }
+                } else if trimmed == "QUIT" || trimmed.starts_with("QUIT ") {
+                    break;
+                }
+
+                line.clear();
+            }
+            result = rx.recv() => {
+                let event = result.unwrap();
+                let skip = matches!(&event, ServerEvent::Chat { from, .. } if *from == username);
+                if !skip {
+                    let line = format!("{}\r\n", event.to_irc(&username));
+                    if writer.write_all(line.as_bytes()).await.is_err() {
+                        guard.mark_broken();
+                        break;
+                    }
+                    if let Some(metrics) = &metrics {
+                        metrics.bytes_relayed_total.inc_by(line.len() as u64);
+                    }
+                }
+            }
+        }
     }
+
+    println!("{}", format!("IRC user '{}' disconnected", username).yellow());
 }
 
 async fn run_client(address: String, username: String) -> Result<(), Box<dyn std::error::Error>> {
@@ -112,7 +821,7 @@ async fn run_client(address: String, username: String) -> Result<(), Box<dyn std
 
     writer.write_all(format!("{}\n", username).as_bytes()).await?;
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let (tx, mut rx) = mpsc::unbounded_channel::<ClientCommand>();
 
     let username_clone = username.clone();
     let mut read_handle = tokio::spawn(async move {
@@ -144,8 +853,9 @@ async fn run_client(address: String, username: String) -> Result<(), Box<dyn std
     });
 
     let mut write_handle = tokio::spawn(async move {
-        while let Some(message) = rx.recv().await {
-            if writer.write_all(format!("{}\n", message).as_bytes()).await.is_err() {
+        while let Some(command) = rx.recv().await {
+            let line = format!("{}\n", command.to_wire());
+            if writer.write_all(line.as_bytes()).await.is_err() {
                 break;
             }
         }
@@ -165,7 +875,7 @@ async fn run_client(address: String, username: String) -> Result<(), Box<dyn std
                 Ok(_) => {
                     let message = input_line.trim();
                     if !message.is_empty() {
-                        if tx_clone.send(message.to_string()).is_err() {
+                        if tx_clone.send(ClientCommand::from_stdin(message)).is_err() {
                             break;
                         }
                     }